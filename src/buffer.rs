@@ -1,5 +1,7 @@
 use crate::rectangle_brush::RectangleBrush;
+use ropey::Rope;
 use std::{
+    collections::HashMap,
     ops::Range,
     path::{Path, PathBuf},
 };
@@ -7,13 +9,17 @@ use syntect::{
     highlighting::{HighlightState, Highlighter, RangedHighlightIterator, Style, ThemeSet},
     parsing::{ParseState, SyntaxSet},
 };
+use unicode_segmentation::UnicodeSegmentation;
 use wgpu_glyph::{GlyphBrush, Point, Scale, SectionText, VariedSection};
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
-    event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode},
+    event::{ElementState, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode},
 };
 
 const SCALE: f32 = 40.0;
+// Thickness of the thin rectangles `CursorShape::Underline`/`HollowBlock`
+// are built from -- thin enough to read as a line, not a filled shape.
+const CURSOR_LINE_THICKNESS: f32 = 2.0;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct Location {
@@ -47,7 +53,9 @@ impl Span {
         self.start.row <= line && self.end.row >= line
     }
 
-    fn get_char_indices_for_line(&self, line: usize, line_length: usize) -> Option<(usize, usize)> {
+    /// `line_length` is a grapheme-cluster count, not a byte or char count --
+    /// `Location::col` is a grapheme index (see `Buffer::line_len`).
+    fn get_grapheme_indices_for_line(&self, line: usize, line_length: usize) -> Option<(usize, usize)> {
         if !self.contains_line(line) {
             return None;
         }
@@ -70,94 +78,461 @@ impl Span {
     }
 }
 
-#[derive(Debug)]
-struct Cursor {
-    location: Location,
-    col_affinity: usize,
-    selection_start: Option<Location>,
+/// A single selection: `anchor` is where it was started and `head` is the
+/// end the caret is drawn at. An empty selection (`anchor == head`) is just
+/// a caret. `Buffer` keeps a `Vec` of these to support multiple cursors; the
+/// common case is simply a `Vec` of length one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Selection {
+    anchor: Location,
+    head: Location,
+    // The visual x position (pixels) the caret was last deliberately placed
+    // at, so Up/Down can track a consistent column over shorter lines.
+    // Refreshed whenever the head moves for any other reason.
+    x_affinity: f32,
 }
 
-impl Cursor {
-    fn new() -> Self {
+impl Selection {
+    fn at(location: Location) -> Self {
         Self {
-            location: Location::new(),
-            col_affinity: 0,
-            selection_start: None,
+            anchor: location,
+            head: location,
+            x_affinity: 0.0,
         }
     }
 
     fn set_row(&mut self, row: usize) {
-        self.location.row = row;
+        self.head.row = row;
     }
 
     fn set_col(&mut self, col: usize) {
-        self.location.col = col;
+        self.head.col = col;
     }
 
-    fn set_col_with_affinity(&mut self, col: usize) {
-        self.location.col = col;
-        self.col_affinity = col;
+    fn collapse(&mut self) {
+        self.anchor = self.head;
     }
 
-    /// Takes the current selection and creates a span.
-    /// Returns `None` if nothing is selected.
-    fn selection_span(&self) -> Option<Span> {
-        let selection_start = match self.selection_start {
-            Some(selection_start) => selection_start,
-            None => return None,
-        };
+    /// `None` if nothing is selected (anchor == head).
+    fn span(&self) -> Option<Span> {
+        if self.anchor == self.head {
+            None
+        } else {
+            Some(Span::new(self.anchor, self.head))
+        }
+    }
+
+    fn bounds(&self) -> (Location, Location) {
+        (self.anchor.min(self.head), self.anchor.max(self.head))
+    }
+}
+
+/// Parser/highlighter state cached at the end of a line, so a re-highlight
+/// can seed from here instead of reparsing from the top of the file.
+#[derive(Clone)]
+struct LineHighlightState {
+    parse: ParseState,
+    highlight: HighlightState,
+}
+
+/// One edit to the rope: remove the char range `start..end` (a no-op range
+/// if `start == end`), then insert `inserted` at `start` if it's `Some`.
+#[derive(Debug, Clone)]
+struct Change {
+    start: usize,
+    end: usize,
+    inserted: Option<String>,
+}
+
+/// A reversible edit, modeled on Helix's transactions: `Change`s plus the
+/// selection state right before and right after (so undo restores the
+/// caret too). `changes` are applied in order, each against the rope as
+/// the ones before it left it, so a batch whose offsets all refer to the
+/// same rope state must be in descending-`start` order.
+#[derive(Debug, Clone)]
+struct Transaction {
+    changes: Vec<Change>,
+    selections_before: Vec<Selection>,
+    selections_after: Vec<Selection>,
+}
+
+impl Transaction {
+    /// Applies `self.changes` to `rope` and returns the transaction that
+    /// exactly undoes it, selections swapped so applying *it* in turn
+    /// restores `selections_before`. Applying the result again redoes
+    /// this transaction, which is how `Buffer::undo`/`redo` share one path.
+    fn apply(&self, rope: &mut Rope) -> Transaction {
+        let mut inverse_changes = Vec::with_capacity(self.changes.len());
+        for change in &self.changes {
+            let removed = if change.end > change.start {
+                let removed = rope.slice(change.start..change.end).to_string();
+                rope.remove(change.start..change.end);
+                removed
+            } else {
+                String::new()
+            };
+            if let Some(text) = &change.inserted {
+                rope.insert(change.start, text);
+            }
+
+            let inserted_len = change.inserted.as_ref().map_or(0, |s| s.chars().count());
+            inverse_changes.push(Change {
+                start: change.start,
+                end: change.start + inserted_len,
+                inserted: if removed.is_empty() { None } else { Some(removed) },
+            });
+        }
+        // Each inverse is only valid against the rope as it was right after
+        // its own change, so they have to run back in the opposite order.
+        inverse_changes.reverse();
+
+        Transaction {
+            changes: inverse_changes,
+            selections_before: self.selections_after.clone(),
+            selections_after: self.selections_before.clone(),
+        }
+    }
 
-        Some(Span::new(selection_start, self.location))
+    /// Folds `newer` into `self` so the pair undoes as a single step.
+    /// `newer`'s changes go first -- they were computed against the rope
+    /// state *after* `self`'s edit.
+    fn coalesce(&mut self, newer: Transaction) {
+        let mut changes = newer.changes;
+        changes.append(&mut self.changes);
+        self.changes = changes;
+        self.selections_before = newer.selections_before;
     }
 }
 
+/// What a single `apply_edit_at_position` call did, in char-offset terms, so
+/// `apply_edit_everywhere` can keep every other cursor consistent with it.
+struct EditResult {
+    /// Row the edit started at, for incremental re-highlighting.
+    edited_from_row: usize,
+    /// The inverse of what was just done, for the undo stack. `None` if
+    /// nothing changed (e.g. backspacing at the start of the document).
+    inverse: Option<Change>,
+    /// Where this edit's own cursor ends up, as an absolute char offset.
+    new_head_idx: usize,
+    /// The net effect on every *other* tracked position: offsets at or past
+    /// `.0` in the pre-edit rope shift by `.1` chars. `None` for a no-op.
+    shift: Option<(usize, isize)>,
+}
+
+/// What kind of edit a transaction came from, so consecutive keystrokes of
+/// the same kind can be coalesced into one undo step (a whole word typed or
+/// backspaced, rather than one step per character).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditKind {
+    Insert,
+    Backspace,
+}
+
+/// The editing mode, Helix-style: `Normal` is where keys trigger motions and
+/// commands, `Insert` types text, and `Select` is `Normal` but motions
+/// extend the selection instead of collapsing it first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Select,
+}
+
+/// How the caret is drawn, mode-dependent (see `Buffer::cursor_shape`):
+/// `Bar` for Insert, `Block` for Normal, `Underline` for Select, and
+/// `HollowBlock` -- `Block`'s four edges with no fill -- for unfocused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CursorShape {
+    Bar,
+    Block,
+    Underline,
+    HollowBlock,
+}
+
+/// One key press, as it matters for keymap lookup -- a `VirtualKeyCode`
+/// plus the modifiers held with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct KeyChord {
+    keycode: VirtualKeyCode,
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+}
+
+impl KeyChord {
+    fn plain(keycode: VirtualKeyCode) -> Self {
+        Self {
+            keycode,
+            ctrl: false,
+            alt: false,
+            shift: false,
+        }
+    }
+
+    fn shifted(keycode: VirtualKeyCode) -> Self {
+        Self {
+            shift: true,
+            ..Self::plain(keycode)
+        }
+    }
+
+    fn from_input(keycode: VirtualKeyCode, modifiers: ModifiersState) -> Self {
+        Self {
+            keycode,
+            ctrl: modifiers.ctrl(),
+            alt: modifiers.alt(),
+            shift: modifiers.shift(),
+        }
+    }
+}
+
+/// Arrow keys stay bound to cursor movement in every mode rather than going
+/// through the keymap -- only the letter-key Normal-mode bindings are
+/// mode-dependent.
+fn is_arrow_key(keycode: VirtualKeyCode) -> bool {
+    matches!(
+        keycode,
+        VirtualKeyCode::Up | VirtualKeyCode::Down | VirtualKeyCode::Left | VirtualKeyCode::Right
+    )
+}
+
+/// A Normal-mode command, named rather than inlined as a closure so the
+/// keymap stays data (key sequence -> command name) instead of code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    MoveWordForward,
+    MoveWordBackward,
+    MoveLineStart,
+    MoveLineEnd,
+    DeleteChar,
+    OpenLineBelow,
+    OpenLineAbove,
+    EnterInsertBeforeCursor,
+    EnterInsertAfterCursor,
+    ToggleSelectMode,
+    GotoFileStart,
+}
+
+/// A node in the keymap trie: either a terminal `Command`, or another level
+/// of chords to keep matching -- how multi-key sequences like `gg` work.
+enum KeyTrieNode {
+    Command(Command),
+    Keymap(HashMap<KeyChord, KeyTrieNode>),
+}
+
+/// Maps Normal/Select-mode key sequences to commands. A `HashMap`-based
+/// trie rather than a flat match so chords with a shared prefix (`gg`)
+/// just nest one level deeper instead of needing their own state machine.
+struct Keymap {
+    root: HashMap<KeyChord, KeyTrieNode>,
+}
+
+impl Keymap {
+    /// The bindings every `Buffer` starts with. Hardcoded for now, but the
+    /// trie shape is what would let this be loaded from a config file.
+    fn default_bindings() -> Self {
+        let mut root = HashMap::new();
+        let bindings = [
+            (KeyChord::plain(VirtualKeyCode::W), Command::MoveWordForward),
+            (KeyChord::plain(VirtualKeyCode::B), Command::MoveWordBackward),
+            (KeyChord::plain(VirtualKeyCode::Home), Command::MoveLineStart),
+            (KeyChord::plain(VirtualKeyCode::End), Command::MoveLineEnd),
+            (KeyChord::plain(VirtualKeyCode::X), Command::DeleteChar),
+            (KeyChord::plain(VirtualKeyCode::O), Command::OpenLineBelow),
+            (KeyChord::shifted(VirtualKeyCode::O), Command::OpenLineAbove),
+            (KeyChord::plain(VirtualKeyCode::I), Command::EnterInsertBeforeCursor),
+            (KeyChord::plain(VirtualKeyCode::A), Command::EnterInsertAfterCursor),
+            (KeyChord::plain(VirtualKeyCode::V), Command::ToggleSelectMode),
+        ];
+        for (chord, command) in bindings {
+            root.insert(chord, KeyTrieNode::Command(command));
+        }
+
+        let mut g_chord = HashMap::new();
+        g_chord.insert(KeyChord::plain(VirtualKeyCode::G), KeyTrieNode::Command(Command::GotoFileStart));
+        root.insert(KeyChord::plain(VirtualKeyCode::G), KeyTrieNode::Keymap(g_chord));
+
+        Self { root }
+    }
+}
+
+const DEFAULT_THEME: &str = "Solarized (dark)";
+
+/// Colors pulled from the active theme's settings for the chrome around the
+/// text (gutter, selection, active line), falling back to a sane default
+/// for themes that don't specify one of these.
+struct ThemeColors {
+    gutter: [f32; 4],
+    selection: [f32; 4],
+    active_line: [f32; 4],
+}
+
+fn color_to_rgba(color: syntect::highlighting::Color) -> [f32; 4] {
+    [
+        color.r as f32 / 255.0,
+        color.g as f32 / 255.0,
+        color.b as f32 / 255.0,
+        color.a as f32 / 255.0,
+    ]
+}
+
+fn theme_colors(theme: &syntect::highlighting::Theme) -> ThemeColors {
+    ThemeColors {
+        gutter: theme
+            .settings
+            .gutter
+            .or(theme.settings.background)
+            .map(color_to_rgba)
+            .unwrap_or([0.06, 0.06, 0.06, 1.0]),
+        selection: theme
+            .settings
+            .selection
+            .map(color_to_rgba)
+            .unwrap_or([0.0, 0.0, 1.0, 0.1]),
+        active_line: theme
+            .settings
+            .line_highlight
+            .map(color_to_rgba)
+            .unwrap_or([1.0, 1.0, 1.0, 0.05]),
+    }
+}
+
+/// Picks the syntax to highlight `path`/`first_line` with: by file
+/// extension first, falling back to sniffing a shebang/modeline on the
+/// first line, and finally plain text so that opening some random file
+/// never panics or silently mis-highlights it as Rust.
+fn detect_syntax<'a>(
+    syntax_set: &'a SyntaxSet,
+    path: &Path,
+    first_line: &str,
+) -> &'a syntect::parsing::SyntaxReference {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| syntax_set.find_syntax_by_extension(ext))
+        .or_else(|| syntax_set.find_syntax_by_first_line(first_line))
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text())
+}
+
+/// Where users can drop extra `.tmTheme` files to have them picked up
+/// alongside the bundled defaults.
+fn user_theme_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/brewcode/themes"))
+}
+
 pub struct Buffer {
-    // TODO: Chunk this at maybe a few thousand lines per chunk?
-    lines: Vec<String>,
-    // Ughh, we have to keep this in sync with the lines vec.
+    // O(log n) line lookup/insert/remove instead of Vec<String> shifting.
+    text: Rope,
+    // Ughh, we have to keep this in sync with the rope's line structure.
     // I don't really want to create a monolith line struct which contains all this info though.
     // We will see how much of a pain it is to maintain this here, if its too difficult maybe we will combine?
     highlight_info: Vec<Vec<(Range<usize>, [f32; 4])>>,
+    // line_states[i] is the state right after line i; re-highlighting from
+    // line N seeds from line_states[N - 1] instead of reparsing from 0.
+    line_states: Vec<LineHighlightState>,
     scroll: f32,
-    cursor: Cursor,
+    // Invariant: never empty, and always sorted so that `primary` stays
+    // valid after `merge_overlapping_selections` re-sorts it.
+    selections: Vec<Selection>,
+    primary: usize,
     dragging: bool,
     size: PhysicalSize<u32>,
     path: PathBuf,
     // TODO: Move those to editor?
     syntax_set: SyntaxSet,
     theme_set: ThemeSet,
+    // Name-based rather than holding the `SyntaxReference`/`Theme` directly,
+    // so they survive edits and theme switches without a lifetime tangle.
+    syntax_name: String,
+    theme_name: String,
+    theme_colors: ThemeColors,
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+    // What the most recently recorded transaction was, so the next one can
+    // decide whether to coalesce into it instead of pushing a new undo
+    // step. Reset to `None` on anything that should break coalescing:
+    // cursor movement, mouse clicks, undo/redo itself, and saving.
+    last_edit_kind: Option<EditKind>,
+    mode: Mode,
+    keymap: Keymap,
+    // Chords matched so far while walking down the keymap trie, e.g. `[g]`
+    // after the first key of `gg` -- cleared once a `Command` is reached or
+    // a key doesn't continue any known chord.
+    pending_chord: Vec<KeyChord>,
+    // Whether this buffer's window has keyboard focus, set by `set_focused`
+    // and consulted only by `cursor_shape` so far.
+    focused: bool,
+}
+
+fn style_color(style: Style) -> [f32; 4] {
+    let Style { foreground, .. } = style;
+    [
+        foreground.r as f32 / 255.0,
+        foreground.g as f32 / 255.0,
+        foreground.b as f32 / 255.0,
+        foreground.a as f32 / 255.0,
+    ]
 }
 
-fn generate_highlight_info(
-    lines: &[String],
-    info: &mut Vec<Vec<(Range<usize>, [f32; 4])>>,
-    syntax_set: &SyntaxSet,
-    theme_set: &ThemeSet,
+/// Queues whatever rectangles `shape` is built from for one caret cell:
+/// `x`/`y` are the top-left of the cell, `width` the glyph-advance width of
+/// the grapheme under the cursor.
+fn draw_caret(
+    rect_brush: &mut RectangleBrush,
+    shape: CursorShape,
+    x: f32,
+    y: f32,
+    width: f32,
+    color: [f32; 4],
 ) {
-    info.clear();
-    // TODO: Not every file is .rs
-    let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
-    let highlighter = Highlighter::new(&theme_set.themes["Solarized (dark)"]);
-    let mut highlight_state = HighlightState::new(&highlighter, Default::default());
-    let mut parse_state = ParseState::new(syntax);
-
-    for line in lines {
-        let ops = parse_state.parse_line(line, syntax_set);
-        let iter = RangedHighlightIterator::new(&mut highlight_state, &ops[..], line, &highlighter);
-        info.push(
-            iter.map(|(Style { foreground, .. }, _, range)| {
-                (
-                    range,
-                    [
-                        foreground.r as f32 / 255.0,
-                        foreground.g as f32 / 255.0,
-                        foreground.b as f32 / 255.0,
-                        foreground.a as f32 / 255.0,
-                    ],
-                )
-            })
-            .collect(),
-        );
+    match shape {
+        CursorShape::Bar => {
+            rect_brush.queue_rectangle(x as i32 - 2, y as i32, 4, SCALE as i32, color);
+        }
+        CursorShape::Block => {
+            rect_brush.queue_rectangle(x as i32, y as i32, width as i32, SCALE as i32, color);
+        }
+        CursorShape::Underline => {
+            rect_brush.queue_rectangle(
+                x as i32,
+                (y + SCALE - CURSOR_LINE_THICKNESS) as i32,
+                width as i32,
+                CURSOR_LINE_THICKNESS as i32,
+                color,
+            );
+        }
+        CursorShape::HollowBlock => {
+            let thickness = CURSOR_LINE_THICKNESS as i32;
+            // top
+            rect_brush.queue_rectangle(x as i32, y as i32, width as i32, thickness, color);
+            // bottom
+            rect_brush.queue_rectangle(
+                x as i32,
+                (y + SCALE) as i32 - thickness,
+                width as i32,
+                thickness,
+                color,
+            );
+            // left
+            rect_brush.queue_rectangle(x as i32, y as i32, thickness, SCALE as i32, color);
+            // right
+            rect_brush.queue_rectangle(
+                x as i32 + width as i32 - thickness,
+                y as i32,
+                thickness,
+                SCALE as i32,
+                color,
+            );
+        }
+    }
+}
+
+/// Overwrites `vec[index]` if it already exists, otherwise pushes.
+fn set_at<T>(vec: &mut Vec<T>, index: usize, value: T) {
+    if index < vec.len() {
+        vec[index] = value;
+    } else {
+        debug_assert_eq!(index, vec.len());
+        vec.push(value);
     }
 }
 
@@ -165,40 +540,431 @@ impl Buffer {
     pub fn new(size: PhysicalSize<u32>, file_name: String) -> Self {
         let path = Path::new(&file_name);
         let file = std::fs::read_to_string(path).expect("Failed to read file.");
-        // TODO: Not sure if just splitting '\n' is right here.
-        // I was using lines, but the trailing empty newline was omitted by lines.
-        let mut lines: Vec<String> = file.split('\n').map(|line| line.to_owned()).collect();
-        // Make sure we have at least one line
-        if lines.is_empty() {
-            lines.push(String::new());
-        }
+        let text = Rope::from_str(&file);
         let syntax_set = SyntaxSet::load_defaults_newlines();
-        let theme_set = ThemeSet::load_defaults();
-        let mut highlight_info = vec![];
-        generate_highlight_info(&lines, &mut highlight_info, &syntax_set, &theme_set);
-        Self {
-            highlight_info,
+        let mut theme_set = ThemeSet::load_defaults();
+        if let Some(dir) = user_theme_dir() {
+            // Not having a (populated) config dir is the common case, not
+            // an error -- just means no extra user themes this run.
+            let _ = theme_set.add_from_folder(dir);
+        }
+
+        let first_line = text.line(0).to_string();
+        let syntax_name = detect_syntax(&syntax_set, path, &first_line).name.clone();
+        let theme_name = DEFAULT_THEME.to_string();
+        let theme_colors = theme_colors(&theme_set.themes[&theme_name]);
+
+        let mut buffer = Self {
+            text,
+            highlight_info: Vec::new(),
+            line_states: Vec::new(),
             scroll: 0.0,
-            lines,
-            cursor: Cursor::new(),
+            selections: vec![Selection::at(Location::new())],
+            primary: 0,
             size,
             path: path.into(),
             syntax_set,
             theme_set,
+            syntax_name,
+            theme_name,
+            theme_colors,
+            dragging: false,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_edit_kind: None,
+            mode: Mode::Normal,
+            keymap: Keymap::default_bindings(),
+            pending_chord: Vec::new(),
+            focused: true,
+        };
+        buffer.rehighlight_from(0);
+        buffer
+    }
+
+    /// Test-only constructor that skips the file read `new` does, so unit
+    /// tests can build a `Buffer` straight from a string.
+    #[cfg(test)]
+    fn for_test(contents: &str) -> Self {
+        let text = Rope::from_str(contents);
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let theme_set = ThemeSet::load_defaults();
+        let theme_name = DEFAULT_THEME.to_string();
+        let theme_colors = theme_colors(&theme_set.themes[&theme_name]);
+
+        let mut buffer = Self {
+            text,
+            highlight_info: Vec::new(),
+            line_states: Vec::new(),
+            scroll: 0.0,
+            selections: vec![Selection::at(Location::new())],
+            primary: 0,
+            size: PhysicalSize::new(800, 600),
+            path: PathBuf::from("test.txt"),
+            syntax_set,
+            theme_set,
+            syntax_name: "Plain Text".to_string(),
+            theme_name,
+            theme_colors,
             dragging: false,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_edit_kind: None,
+            mode: Mode::Normal,
+            keymap: Keymap::default_bindings(),
+            pending_chord: Vec::new(),
+            focused: true,
+        };
+        buffer.rehighlight_from(0);
+        buffer
+    }
+
+    /// Names of every loaded theme (bundled defaults plus anything found
+    /// in the user theme dir), for a theme picker to list.
+    pub fn theme_names(&self) -> Vec<&str> {
+        self.theme_set.themes.keys().map(String::as_str).collect()
+    }
+
+    /// Switches the active theme and re-highlights the whole buffer with
+    /// it. A `theme_name` that isn't loaded (a typo, a stale config value,
+    /// a `.tmTheme` file that's since been removed from the user theme
+    /// dir) is a no-op rather than a panic.
+    pub fn set_theme(&mut self, theme_name: impl Into<String>) {
+        let theme_name = theme_name.into();
+        if !self.theme_set.themes.contains_key(&theme_name) {
+            return;
         }
+        self.theme_name = theme_name;
+        self.theme_colors = theme_colors(&self.theme_set.themes[&self.theme_name]);
+        self.rehighlight_from(0);
     }
 
-    pub fn save(&self) {
-        std::fs::write(&self.path, self.lines.join("\n")).expect("Failed to save file.");
+    /// The current editing mode, for callers that need to render it (the
+    /// caret shape) or otherwise react to it.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn save(&mut self) {
+        std::fs::write(&self.path, self.text.to_string()).expect("Failed to save file.");
+        self.last_edit_kind = None;
     }
 
     pub fn update_size(&mut self, size: PhysicalSize<u32>) {
         self.size = size;
     }
 
+    /// Tracks whether this buffer's window currently has keyboard focus, so
+    /// `draw` can swap a filled `Block` caret for `HollowBlock` while
+    /// unfocused.
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// The shape the caret should currently render as: mode-dependent, with
+    /// a focused `Block` hollowed out while the window is unfocused.
+    fn cursor_shape(&self) -> CursorShape {
+        let shape = match self.mode {
+            Mode::Normal => CursorShape::Block,
+            Mode::Insert => CursorShape::Bar,
+            Mode::Select => CursorShape::Underline,
+        };
+        if shape == CursorShape::Block && !self.focused {
+            CursorShape::HollowBlock
+        } else {
+            shape
+        }
+    }
+
+    fn line_count(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    /// Length of line `row` in chars, excluding a trailing `\n` (ropey's
+    /// `Line::len_chars` includes it); a `\r` before it is counted. Cursor movement
+    /// uses the grapheme-cluster-counting `line_len` below instead.
+    fn line_char_len(&self, row: usize) -> usize {
+        let line = self.text.line(row);
+        let len = line.len_chars();
+        if len > 0 && line.char(len - 1) == '\n' {
+            len - 1
+        } else {
+            len
+        }
+    }
+
+    fn line_to_string(&self, row: usize) -> String {
+        let line = self.text.line(row);
+        let len = self.line_char_len(row);
+        line.slice(0..len).to_string()
+    }
+
+    /// Length of line `row` in grapheme clusters -- the unit `Location::col`
+    /// counts in, so movement and hit-testing land on whole characters.
+    fn line_len(&self, row: usize) -> usize {
+        self.line_to_string(row).graphemes(true).count()
+    }
+
+    /// Byte offset of the start of the `col`-th grapheme cluster in
+    /// `line`, or `line.len()` if `col` is at or past the end of the line.
+    fn grapheme_byte_offset(line: &str, col: usize) -> usize {
+        line.grapheme_indices(true)
+            .nth(col)
+            .map(|(byte_idx, _)| byte_idx)
+            .unwrap_or_else(|| line.len())
+    }
+
+    /// Char offset of the `col`-th grapheme cluster within `line` (rope
+    /// operations are char-indexed, so this is the bridge between a
+    /// grapheme column and a rope position).
+    fn col_to_char_offset(line: &str, col: usize) -> usize {
+        let byte_offset = Self::grapheme_byte_offset(line, col);
+        line[..byte_offset].chars().count()
+    }
+
+    fn location_to_char_idx(&self, location: Location) -> usize {
+        let line = self.line_to_string(location.row);
+        self.text.line_to_char(location.row) + Self::col_to_char_offset(&line, location.col)
+    }
+
+    /// Inverse of `location_to_char_idx`: the row/col of the grapheme
+    /// cluster `idx` chars into the rope falls in.
+    fn char_idx_to_location(&self, idx: usize) -> Location {
+        let row = self.text.char_to_line(idx);
+        let col_chars = idx - self.text.line_to_char(row);
+        let line = self.line_to_string(row);
+        let byte_offset = line
+            .char_indices()
+            .nth(col_chars)
+            .map(|(byte_idx, _)| byte_idx)
+            .unwrap_or_else(|| line.len());
+        let col = line[..byte_offset].graphemes(true).count();
+        Location { row, col }
+    }
+
+    /// Cumulative glyph-advance x position (pixels) at every grapheme
+    /// boundary of `line`, including one past the last grapheme -- real font
+    /// metrics instead of assuming a fixed monospace advance.
+    fn grapheme_advances(line: &str, glyph_brush: &GlyphBrush<()>) -> Vec<f32> {
+        let layout = glyph_brush.fonts().first().unwrap().layout(
+            line,
+            Scale::uniform(SCALE),
+            Point { x: 0.0, y: 0.0 },
+        );
+        let mut char_boundary_x = vec![0.0f32];
+        for positioned_glyph in layout {
+            let x = char_boundary_x.last().copied().unwrap_or(0.0)
+                + positioned_glyph.unpositioned().h_metrics().advance_width;
+            char_boundary_x.push(x);
+        }
+
+        line.grapheme_indices(true)
+            .map(|(byte_idx, _)| char_boundary_x[line[..byte_idx].chars().count()])
+            .chain(std::iter::once(*char_boundary_x.last().unwrap()))
+            .collect()
+    }
+
+    fn col_to_x(&self, row: usize, col: usize, glyph_brush: &GlyphBrush<()>) -> f32 {
+        let line = self.line_to_string(row);
+        let advances = Self::grapheme_advances(&line, glyph_brush);
+        advances[col.min(advances.len() - 1)]
+    }
+
+    /// Maps a pixel x position to the grapheme boundary whose advance is
+    /// closest to it.
+    fn x_to_col(&self, row: usize, x: f32, glyph_brush: &GlyphBrush<()>) -> usize {
+        let line = self.line_to_string(row);
+        let advances = Self::grapheme_advances(&line, glyph_brush);
+        advances
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| (**a - x).abs().partial_cmp(&(**b - x).abs()).unwrap())
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    /// Re-runs syntax highlighting from `from_line`, seeding from the line
+    /// above's cached state, and stops early once a line's freshly computed
+    /// end state matches what was cached at that line's *pre-edit* row (a
+    /// fixpoint -- no multiline construct changed past this point). `from_line
+    /// >= line_count()` means nothing was actually edited, so it's a no-op.
+    fn rehighlight_from(&mut self, from_line: usize) {
+        if from_line != 0 && from_line >= self.line_count() {
+            return;
+        }
+
+        let syntax = self
+            .syntax_set
+            .find_syntax_by_name(&self.syntax_name)
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        let theme = self
+            .theme_set
+            .themes
+            .get(&self.theme_name)
+            .unwrap_or_else(|| &self.theme_set.themes[DEFAULT_THEME]);
+        let highlighter = Highlighter::new(theme);
+
+        let (mut parse_state, mut highlight_state) = if from_line == 0 {
+            (
+                ParseState::new(syntax),
+                HighlightState::new(&highlighter, Default::default()),
+            )
+        } else {
+            let seed = &self.line_states[from_line - 1];
+            (seed.parse.clone(), seed.highlight.clone())
+        };
+
+        // Snapshot the pre-edit cache before we start overwriting it below, so
+        // the fixpoint check can compare a line against its own pre-edit row
+        // even when the edit shifted the line count.
+        let old_line_states = self.line_states.clone();
+        let old_highlight_info = self.highlight_info.clone();
+        let line_delta = self.line_count() as isize - old_line_states.len() as isize;
+
+        let mut row = from_line;
+        loop {
+            if row >= self.line_count() {
+                self.highlight_info.truncate(row);
+                self.line_states.truncate(row);
+                break;
+            }
+
+            let line = self.line_to_string(row);
+            let ops = parse_state.parse_line(&line, &self.syntax_set);
+            let spans: Vec<(Range<usize>, [f32; 4])> =
+                RangedHighlightIterator::new(&mut highlight_state, &ops[..], &line, &highlighter)
+                    .map(|(style, _, range)| (range, style_color(style)))
+                    .collect();
+
+            let old_row = row as isize - line_delta;
+            let reached_fixpoint = row > from_line
+                && old_row >= 0
+                && old_line_states
+                    .get(old_row as usize)
+                    .map_or(false, |cached| {
+                        cached.parse == parse_state && cached.highlight == highlight_state
+                    });
+
+            set_at(&mut self.highlight_info, row, spans);
+            set_at(
+                &mut self.line_states,
+                row,
+                LineHighlightState {
+                    parse: parse_state.clone(),
+                    highlight: highlight_state.clone(),
+                },
+            );
+
+            if reached_fixpoint {
+                // Keep the pre-edit cache for the lines below, moved by
+                // `line_delta` rows.
+                let rest = old_row as usize + 1;
+                self.highlight_info.truncate(row + 1);
+                self.highlight_info.extend_from_slice(&old_highlight_info[rest..]);
+                self.line_states.truncate(row + 1);
+                self.line_states.extend_from_slice(&old_line_states[rest..]);
+                break;
+            }
+            row += 1;
+        }
+
+        debug_assert_eq!(self.highlight_info.len(), self.line_count());
+        debug_assert_eq!(self.line_states.len(), self.line_count());
+    }
+
+    fn primary(&self) -> &Selection {
+        &self.selections[self.primary]
+    }
+
+    /// Collapses `selections[index]` to its head before a motion -- except
+    /// in `Select` mode, where a motion should instead extend the selection
+    /// by leaving the anchor where it is.
+    fn collapse_unless_select(&mut self, index: usize) {
+        if self.mode != Mode::Select {
+            self.selections[index].collapse();
+        }
+    }
+
+    /// Runs a motion across every selection: collapses each to its head
+    /// (unless `Select` mode wants it extended), asks `f` for its new
+    /// location and affinity, merges any selections the motion brought
+    /// together, and clears `last_edit_kind`. Shared by every arrow key
+    /// and Normal-mode motion command.
+    fn move_all_selections(
+        &mut self,
+        glyph_brush: &GlyphBrush<()>,
+        mut f: impl FnMut(&Buffer, Location, f32, &GlyphBrush<()>) -> (Location, f32),
+    ) {
+        for index in 0..self.selections.len() {
+            self.collapse_unless_select(index);
+            let head = self.selections[index].head;
+            let x_affinity = self.selections[index].x_affinity;
+            let (location, x_affinity) = f(self, head, x_affinity, glyph_brush);
+            let selection = &mut self.selections[index];
+            selection.set_row(location.row);
+            selection.set_col(location.col);
+            selection.x_affinity = x_affinity;
+        }
+        self.merge_overlapping_selections();
+        self.last_edit_kind = None;
+    }
+
+    /// Sorts selections into document order, merging any that overlap or
+    /// touch. `primary` is re-pointed at whichever merged selection now
+    /// contains the location it used to point at.
+    fn merge_overlapping_selections(&mut self) {
+        let primary_head = self.primary().head;
+        self.selections.sort_by_key(|s| s.bounds().0);
+
+        let mut merged: Vec<Selection> = Vec::with_capacity(self.selections.len());
+        for selection in self.selections.drain(..) {
+            let (start, end) = selection.bounds();
+            if let Some(last) = merged.last_mut() {
+                let (last_start, last_end) = last.bounds();
+                if start <= last_end {
+                    // Overlapping or touching (two bare carets landing on
+                    // the same spot overlap trivially) -- fold into one,
+                    // growing the range and keeping the head at the end.
+                    *last = Selection {
+                        anchor: start.min(last_start),
+                        head: end.max(last_end),
+                        x_affinity: last.x_affinity,
+                    };
+                    continue;
+                }
+            }
+            merged.push(selection);
+        }
+
+        self.selections = merged;
+        self.primary = self
+            .selections
+            .iter()
+            .position(|s| {
+                let (start, end) = s.bounds();
+                start <= primary_head && primary_head <= end
+            })
+            .unwrap_or(0);
+    }
+
+    /// Adds a new caret on the line above (`delta < 0`) or below
+    /// (`delta > 0`) the primary selection, at its affinity column.
+    fn add_cursor_vertically(&mut self, delta: isize, glyph_brush: &GlyphBrush<()>) {
+        let primary = *self.primary();
+        let row = (primary.head.row as isize + delta)
+            .max(0)
+            .min(self.line_count() as isize - 1) as usize;
+        let col = self.x_to_col(row, primary.x_affinity, glyph_brush);
+        self.selections.push(Selection {
+            anchor: Location { row, col },
+            head: Location { row, col },
+            x_affinity: primary.x_affinity,
+        });
+        self.primary = self.selections.len() - 1;
+        self.merge_overlapping_selections();
+    }
+
     fn ensure_cursor_in_view(&mut self) {
-        let cursor_y = self.cursor.location.row as f32 * SCALE;
+        let cursor_y = self.primary().head.row as f32 * SCALE;
         let bottom = self.scroll + self.size.height as f32;
 
         if cursor_y < self.scroll {
@@ -209,11 +975,11 @@ impl Buffer {
     }
 
     pub fn scroll(&mut self, delta: f32) {
-        let max_scroll = if self.lines.is_empty() {
+        let max_scroll = if self.line_count() == 0 {
             0.0
         } else {
             // TODO: Find better way to calculate max scroll based on line count
-            ((self.lines.len() - 1) as f32 * SCALE) + 5.0
+            ((self.line_count() - 1) as f32 * SCALE) + 5.0
         };
 
         self.scroll = (self.scroll + delta).max(0.0).min(max_scroll);
@@ -224,34 +990,38 @@ impl Buffer {
         button: MouseButton,
         state: ElementState,
         position: PhysicalPosition<i32>,
+        glyph_brush: &GlyphBrush<()>,
     ) {
         if button == MouseButton::Left {
             if state == ElementState::Pressed {
-                self.cursor.selection_start = None;
-                let location = self.hit_test(position);
-                self.cursor.set_row(location.row);
-                self.cursor.set_col_with_affinity(location.col);
+                // A plain click collapses multi-cursor state to one caret.
+                let location = self.hit_test(position, glyph_brush);
+                let mut selection = Selection::at(location);
+                selection.x_affinity = self.col_to_x(location.row, location.col, glyph_brush);
+                self.selections = vec![selection];
+                self.primary = 0;
                 self.dragging = true;
+                self.last_edit_kind = None;
             } else {
                 self.dragging = false;
             }
         }
     }
 
-    pub fn handle_mouse_move(&mut self, position: PhysicalPosition<i32>) {
+    pub fn handle_mouse_move(&mut self, position: PhysicalPosition<i32>, glyph_brush: &GlyphBrush<()>) {
         if self.dragging {
-            if self.cursor.selection_start.is_none() {
-                self.cursor.selection_start = Some(self.cursor.location);
-            }
-            let location = self.hit_test(position);
-            self.cursor.set_row(location.row);
-            self.cursor.set_col_with_affinity(location.col);
+            let location = self.hit_test(position, glyph_brush);
+            let x_affinity = self.col_to_x(location.row, location.col, glyph_brush);
+            let primary = &mut self.selections[self.primary];
+            primary.set_col(location.col);
+            primary.set_row(location.row);
+            primary.x_affinity = x_affinity;
         }
     }
 
-    fn hit_test(&self, position: PhysicalPosition<i32>) -> Location {
+    fn hit_test(&self, position: PhysicalPosition<i32>, glyph_brush: &GlyphBrush<()>) -> Location {
         let x_pad = 10.0;
-        let digit_count = self.lines.len().to_string().chars().count();
+        let digit_count = self.line_count().to_string().chars().count();
         let gutter_offset = x_pad + 30.0 + digit_count as f32 * (SCALE / 2.0);
 
         let abs_position = PhysicalPosition::new(
@@ -260,65 +1030,320 @@ impl Buffer {
         );
 
         let line = (abs_position.y / 40.0).floor() as usize;
-        if line >= self.lines.len() {
-            let row = self.lines.len() - 1;
-            let col = self.lines.last().unwrap().len();
+        if line >= self.line_count() {
+            let row = self.line_count() - 1;
+            let col = self.line_len(row);
             Location { row, col }
         } else {
-            // TODO: HACK this should not be hardcoded
-            let h_advance = 19.065777;
-            let col = (abs_position.x / h_advance).round() as usize;
             let row = line;
-            let col = col.min(self.lines[line].len());
+            let col = self.x_to_col(row, abs_position.x, glyph_brush);
             Location { row, col }
         }
     }
 
-    pub fn handle_char_input(&mut self, input: char) {
+    /// Applies `input` at the single absolute char position `head_idx`
+    /// (not a `Location` -- callers need char-offset space to shift every
+    /// *other* tracked position by this edit's net effect).
+    fn apply_edit_at_position(&mut self, head_idx: usize, input: char) -> EditResult {
+        let location = self.char_idx_to_location(head_idx);
         if input == '\n' || input == '\r' {
-            let new_line = self.lines[self.cursor.location.row].split_off(self.cursor.location.col);
-            self.cursor.set_row(self.cursor.location.row + 1);
-            self.lines.insert(self.cursor.location.row, new_line);
-            self.cursor.set_col_with_affinity(0);
+            self.text.insert_char(head_idx, '\n');
+            EditResult {
+                edited_from_row: location.row,
+                inverse: Some(Change {
+                    start: head_idx,
+                    end: head_idx + 1,
+                    inserted: None,
+                }),
+                new_head_idx: head_idx + 1,
+                shift: Some((head_idx, 1)),
+            }
         // this is Backspace
         } else if input == '\u{8}' {
-            if self.cursor.location.col > 0 {
-                self.lines[self.cursor.location.row].remove(self.cursor.location.col - 1);
-                self.cursor
-                    .set_col_with_affinity(self.cursor.location.col - 1);
-            } else if self.cursor.location.row > 0 {
-                let remaining = self.lines.remove(self.cursor.location.row);
-                self.cursor.set_row(self.cursor.location.row - 1);
-                self.cursor
-                    .set_col_with_affinity(self.lines[self.cursor.location.row].len());
-                self.lines[self.cursor.location.row].push_str(&remaining);
+            if location.col > 0 {
+                let line = self.line_to_string(location.row);
+                let base = self.text.line_to_char(location.row);
+                let start = base + Self::col_to_char_offset(&line, location.col - 1);
+                let end = base + Self::col_to_char_offset(&line, location.col);
+                let removed = self.text.slice(start..end).to_string();
+                self.text.remove(start..end);
+                EditResult {
+                    edited_from_row: location.row,
+                    inverse: Some(Change {
+                        start,
+                        end: start,
+                        inserted: Some(removed),
+                    }),
+                    new_head_idx: start,
+                    shift: Some((end, -((end - start) as isize))),
+                }
+            } else if location.row > 0 {
+                // Removing the line terminator before us merges this line
+                // into the previous one.
+                let removed = self.text.slice(head_idx - 1..head_idx).to_string();
+                self.text.remove(head_idx - 1..head_idx);
+                EditResult {
+                    edited_from_row: location.row - 1,
+                    inverse: Some(Change {
+                        start: head_idx - 1,
+                        end: head_idx - 1,
+                        inserted: Some(removed),
+                    }),
+                    new_head_idx: head_idx - 1,
+                    shift: Some((head_idx, -1)),
+                }
+            } else {
+                EditResult {
+                    edited_from_row: location.row,
+                    inverse: None,
+                    new_head_idx: head_idx,
+                    shift: None,
+                }
             }
         // this is Delete
         } else if input == '\u{7f}' {
-            if self.lines[self.cursor.location.row].len() > self.cursor.location.col {
-                self.lines[self.cursor.location.row].remove(self.cursor.location.col);
+            if self.line_len(location.row) > location.col {
+                let line = self.line_to_string(location.row);
+                let base = self.text.line_to_char(location.row);
+                let start = base + Self::col_to_char_offset(&line, location.col);
+                let end = base + Self::col_to_char_offset(&line, location.col + 1);
+                let removed = self.text.slice(start..end).to_string();
+                self.text.remove(start..end);
+                EditResult {
+                    edited_from_row: location.row,
+                    inverse: Some(Change {
+                        start,
+                        end: start,
+                        inserted: Some(removed),
+                    }),
+                    new_head_idx: head_idx,
+                    shift: Some((end, -((end - start) as isize))),
+                }
+            } else {
+                EditResult {
+                    edited_from_row: location.row,
+                    inverse: None,
+                    new_head_idx: head_idx,
+                    shift: None,
+                }
             }
         } else if input == '\t' {
             // Do nothing, unless we consider how to display tab,
             // because now cursor should be moved to right one character when deleting
             // Also, now when there is \t in the file, it will not be displayed correctly
+            EditResult {
+                edited_from_row: location.row,
+                inverse: None,
+                new_head_idx: head_idx,
+                shift: None,
+            }
         } else {
-            self.lines[self.cursor.location.row].insert(self.cursor.location.col, input);
-            self.cursor.set_col(self.cursor.location.col + 1);
+            self.text.insert_char(head_idx, input);
+            EditResult {
+                edited_from_row: location.row,
+                inverse: Some(Change {
+                    start: head_idx,
+                    end: head_idx + 1,
+                    inserted: None,
+                }),
+                new_head_idx: head_idx + 1,
+                shift: Some((head_idx, 1)),
+            }
+        }
+    }
+
+    /// Applies `input` at every selection. Shared by the public, mode-gated
+    /// `handle_char_input` (typed text) and the Normal-mode commands that
+    /// edit text directly (`x`, `o`, `O`), which need this regardless of
+    /// mode.
+    fn apply_edit_everywhere(&mut self, input: char, glyph_brush: &GlyphBrush<()>) {
+        self.apply_edit_everywhere_core(input);
+        for index in 0..self.selections.len() {
+            let head = self.selections[index].head;
+            self.selections[index].x_affinity = self.col_to_x(head.row, head.col, glyph_brush);
+        }
+    }
+
+    /// The glyph-independent core of `apply_edit_everywhere`, split out so
+    /// it can be unit tested without a GPU context. Every selection's
+    /// anchor/head is tracked as an absolute char offset for the batch, and
+    /// each edit's net effect (`EditResult::shift`) is applied to every
+    /// *other* tracked offset -- including not-yet-recorded undo
+    /// `Change`s -- so two same-row cursors stay consistent with each
+    /// other as they're processed, the same idea as Helix's
+    /// `Transaction::map_position`.
+    fn apply_edit_everywhere_core(&mut self, input: char) {
+        let selections_before = self.selections.clone();
+
+        let mut head_idx: Vec<usize> = self
+            .selections
+            .iter()
+            .map(|s| self.location_to_char_idx(s.head))
+            .collect();
+        let mut anchor_idx: Vec<usize> = self
+            .selections
+            .iter()
+            .map(|s| self.location_to_char_idx(s.anchor))
+            .collect();
+
+        let mut min_edit_row: Option<usize> = None;
+        let mut inverse_changes: Vec<Change> = Vec::new();
+        for index in 0..self.selections.len() {
+            let result = self.apply_edit_at_position(head_idx[index], input);
+
+            if let Some((at, delta)) = result.shift {
+                for change in inverse_changes.iter_mut() {
+                    if change.start >= at {
+                        change.start = (change.start as isize + delta) as usize;
+                    }
+                    if change.end >= at {
+                        change.end = (change.end as isize + delta) as usize;
+                    }
+                }
+                for other in 0..head_idx.len() {
+                    if other == index {
+                        continue;
+                    }
+                    if head_idx[other] >= at {
+                        head_idx[other] = (head_idx[other] as isize + delta) as usize;
+                    }
+                    if anchor_idx[other] >= at {
+                        anchor_idx[other] = (anchor_idx[other] as isize + delta) as usize;
+                    }
+                }
+                // Selections are in document order, so later edits never
+                // move the rows of earlier ones.
+                min_edit_row = Some(min_edit_row.map_or(result.edited_from_row, |row| row.min(result.edited_from_row)));
+            }
+            if let Some(change) = result.inverse {
+                inverse_changes.push(change);
+            }
+            head_idx[index] = result.new_head_idx;
+            anchor_idx[index] = result.new_head_idx;
+        }
+
+        for index in 0..self.selections.len() {
+            self.selections[index].head = self.char_idx_to_location(head_idx[index]);
+            self.selections[index].anchor = self.char_idx_to_location(anchor_idx[index]);
         }
+
+        self.merge_overlapping_selections();
         self.ensure_cursor_in_view();
-        // TODO: recalculating highlighting every time an edit happes is pretty expensive
-        // We should minimize the amount of recomputation and maybe allow for highlighting to be done
-        // in a more async manner?
-        generate_highlight_info(
-            &self.lines,
-            &mut self.highlight_info,
-            &self.syntax_set,
-            &self.theme_set,
-        );
+        // Only re-highlight from the topmost edited line; `rehighlight_from`
+        // stops early once it hits a line whose end state didn't change.
+        let edited_from = min_edit_row.unwrap_or(usize::MAX);
+        self.rehighlight_from(edited_from);
+
+        if !inverse_changes.is_empty() {
+            // `Transaction::apply` needs changes in descending-start order.
+            inverse_changes.sort_by_key(|c| std::cmp::Reverse(c.start));
+            let undo_transaction = Transaction {
+                changes: inverse_changes,
+                selections_before: self.selections.clone(),
+                selections_after: selections_before,
+            };
+            self.record_undo(undo_transaction, input);
+        }
     }
 
-    pub fn handle_keyboard_input(&mut self, input: KeyboardInput) {
+    /// Mutates text from typed input -- a no-op outside `Insert` mode, since
+    /// in `Normal`/`Select` mode the same keys instead trigger motions and
+    /// commands via `handle_keyboard_input`/`run_command`.
+    pub fn handle_char_input(&mut self, input: char, glyph_brush: &GlyphBrush<()>) {
+        if self.mode != Mode::Insert {
+            return;
+        }
+        self.apply_edit_everywhere(input, glyph_brush);
+    }
+
+    /// Letters, digits and underscores are word chars, everything else
+    /// (whitespace, punctuation) is a boundary. `next_word_start` and
+    /// `prev_word_start` use Unicode word segmentation instead, so their
+    /// word boundaries can differ from this.
+    fn is_word_char(c: char) -> bool {
+        c.is_alphanumeric() || c == '_'
+    }
+
+    /// Whether `transaction` crosses a word boundary, so `record_undo` can
+    /// break coalescing there even though the edit kind didn't change -- e.g.
+    /// typing "hello world" shouldn't coalesce into one undo step just
+    /// because every character was an `Insert`.
+    fn crosses_word_boundary(transaction: &Transaction, input: char) -> bool {
+        if input == '\u{8}' {
+            transaction
+                .changes
+                .iter()
+                .filter_map(|change| change.inserted.as_deref())
+                .flat_map(str::chars)
+                .any(|c| !Self::is_word_char(c))
+        } else {
+            !Self::is_word_char(input)
+        }
+    }
+
+    /// Pushes `transaction` onto the undo stack and clears the redo stack.
+    /// Coalesces into the previous undo step instead of pushing a new one
+    /// when `input` continues the same word of insertions or backspaces.
+    fn record_undo(&mut self, transaction: Transaction, input: char) {
+        self.redo_stack.clear();
+
+        let kind = if input == '\u{8}' {
+            Some(EditKind::Backspace)
+        } else if input != '\u{7f}' && input != '\t' && input != '\n' && input != '\r' {
+            Some(EditKind::Insert)
+        } else {
+            None
+        };
+
+        let coalesces = kind.is_some()
+            && kind == self.last_edit_kind
+            && !Self::crosses_word_boundary(&transaction, input);
+
+        if coalesces {
+            if let Some(top) = self.undo_stack.last_mut() {
+                top.coalesce(transaction);
+                self.last_edit_kind = kind;
+                return;
+            }
+        }
+
+        self.last_edit_kind = kind;
+        self.undo_stack.push(transaction);
+    }
+
+    /// Applies the inverse of the most recent transaction, restoring the
+    /// text and selections as they were right before it.
+    pub fn undo(&mut self) {
+        let transaction = match self.undo_stack.pop() {
+            Some(transaction) => transaction,
+            None => return,
+        };
+        let redo_transaction = transaction.apply(&mut self.text);
+        self.selections = transaction.selections_after.clone();
+        self.primary = self.primary.min(self.selections.len() - 1);
+        self.last_edit_kind = None;
+        self.ensure_cursor_in_view();
+        self.rehighlight_from(0);
+        self.redo_stack.push(redo_transaction);
+    }
+
+    /// Re-applies the transaction most recently undone, the mirror image of
+    /// `undo`.
+    pub fn redo(&mut self) {
+        let transaction = match self.redo_stack.pop() {
+            Some(transaction) => transaction,
+            None => return,
+        };
+        let undo_transaction = transaction.apply(&mut self.text);
+        self.selections = transaction.selections_after.clone();
+        self.primary = self.primary.min(self.selections.len() - 1);
+        self.last_edit_kind = None;
+        self.ensure_cursor_in_view();
+        self.rehighlight_from(0);
+        self.undo_stack.push(undo_transaction);
+    }
+
+    pub fn handle_keyboard_input(&mut self, input: KeyboardInput, glyph_brush: &GlyphBrush<()>) {
         let keycode = match input.virtual_keycode {
             Some(keycode) => keycode,
             None => return,
@@ -328,57 +1353,282 @@ impl Buffer {
             return;
         }
 
+        let ctrl = input.modifiers.ctrl();
+        let ctrl_alt = ctrl && input.modifiers.alt();
+
+        // Escape always returns to Normal mode (dropping any chord we were
+        // midway through matching), regardless of what mode we were in --
+        // handled ahead of the data-driven keymap below.
+        if keycode == VirtualKeyCode::Escape {
+            self.mode = Mode::Normal;
+            self.pending_chord.clear();
+            for index in 0..self.selections.len() {
+                self.selections[index].collapse();
+            }
+            self.last_edit_kind = None;
+            return;
+        }
+
+        // Outside Insert mode, keys other than the ones still handled below
+        // (undo/redo, multi-cursor, arrow navigation) go through the keymap
+        // instead of falling through to `_ => {}`.
+        if self.mode != Mode::Insert && !ctrl && !is_arrow_key(keycode) {
+            self.handle_normal_mode_key(keycode, input.modifiers, glyph_brush);
+            return;
+        }
+
         // TODO: Support changing selection via Shift modifier and arrow keys!
         // Should be pretty easy: don't reset selection start if Shift modifier is active.
         match keycode {
+            VirtualKeyCode::Z if ctrl && input.modifiers.shift() => self.redo(),
+            VirtualKeyCode::Z if ctrl => self.undo(),
+            VirtualKeyCode::Up if ctrl_alt => self.add_cursor_vertically(-1, glyph_brush),
+            VirtualKeyCode::Down if ctrl_alt => self.add_cursor_vertically(1, glyph_brush),
+            // Up/Down preserve `x_affinity` (the visual column last chosen
+            // deliberately) rather than recomputing it, so moving through
+            // shorter lines and back lands the caret where it started.
             VirtualKeyCode::Up => {
-                self.cursor.selection_start = None;
-                let row = (self.cursor.location.row as isize - 1)
-                    .max(0)
-                    .min(self.lines.len() as isize) as usize;
-                let col = self.lines[row].len().min(self.cursor.col_affinity);
-                self.cursor.set_row(row);
-                self.cursor.set_col(col);
+                self.move_all_selections(glyph_brush, |buffer, head, x_affinity, glyph_brush| {
+                    let row = (head.row as isize - 1).max(0).min(buffer.line_count() as isize) as usize;
+                    let col = buffer.x_to_col(row, x_affinity, glyph_brush);
+                    (Location { row, col }, x_affinity)
+                });
             }
             VirtualKeyCode::Down => {
-                self.cursor.selection_start = None;
-                let row = (self.cursor.location.row as isize + 1)
-                    .max(0)
-                    .min(self.lines.len() as isize - 1) as usize;
-                let col = self.lines[row].len().min(self.cursor.col_affinity);
-                self.cursor.set_row(row);
-                self.cursor.set_col(col);
+                self.move_all_selections(glyph_brush, |buffer, head, x_affinity, glyph_brush| {
+                    let row = (head.row as isize + 1)
+                        .max(0)
+                        .min(buffer.line_count() as isize - 1) as usize;
+                    let col = buffer.x_to_col(row, x_affinity, glyph_brush);
+                    (Location { row, col }, x_affinity)
+                });
             }
             VirtualKeyCode::Left => {
-                self.cursor.selection_start = None;
-                if self.cursor.location.col == 0 {
-                    if self.cursor.location.row > 0 {
-                        self.cursor.set_row(self.cursor.location.row - 1);
-                        self.cursor
-                            .set_col_with_affinity(self.lines[self.cursor.location.row].len());
-                    }
-                } else {
-                    self.cursor
-                        .set_col_with_affinity(self.cursor.location.col - 1);
-                }
+                self.move_all_selections(glyph_brush, |buffer, head, _x_affinity, glyph_brush| {
+                    let (row, col) = if head.col == 0 {
+                        if head.row > 0 {
+                            (head.row - 1, buffer.line_len(head.row - 1))
+                        } else {
+                            (head.row, head.col)
+                        }
+                    } else {
+                        (head.row, head.col - 1)
+                    };
+                    let x_affinity = buffer.col_to_x(row, col, glyph_brush);
+                    (Location { row, col }, x_affinity)
+                });
             }
             VirtualKeyCode::Right => {
-                self.cursor.selection_start = None;
-                if self.cursor.location.col >= self.lines[self.cursor.location.row].len() {
-                    if self.cursor.location.row < self.lines.len() - 1 {
-                        self.cursor.set_row(self.cursor.location.row + 1);
-                        self.cursor.set_col_with_affinity(0);
-                    }
-                } else {
-                    self.cursor
-                        .set_col_with_affinity(self.cursor.location.col + 1);
-                }
+                self.move_all_selections(glyph_brush, |buffer, head, _x_affinity, glyph_brush| {
+                    let (row, col) = if head.col >= buffer.line_len(head.row) {
+                        if head.row < buffer.line_count() - 1 {
+                            (head.row + 1, 0)
+                        } else {
+                            (head.row, head.col)
+                        }
+                    } else {
+                        (head.row, head.col + 1)
+                    };
+                    let x_affinity = buffer.col_to_x(row, col, glyph_brush);
+                    (Location { row, col }, x_affinity)
+                });
             }
             _ => {}
         }
         self.ensure_cursor_in_view();
     }
 
+    /// Walks `keycode` down the keymap trie from wherever `pending_chord`
+    /// left off. A `Command` runs immediately and resets the chord; a
+    /// nested `Keymap` extends it so the next key keeps matching (how `gg`
+    /// works); a key that matches nothing drops the chord silently.
+    fn handle_normal_mode_key(&mut self, keycode: VirtualKeyCode, modifiers: ModifiersState, glyph_brush: &GlyphBrush<()>) {
+        self.pending_chord.push(KeyChord::from_input(keycode, modifiers));
+
+        let mut node = &self.keymap.root;
+        let mut resolved = None;
+        let mut is_partial = false;
+        for chord in &self.pending_chord {
+            match node.get(chord) {
+                Some(KeyTrieNode::Command(command)) => {
+                    resolved = Some(*command);
+                    break;
+                }
+                Some(KeyTrieNode::Keymap(next)) => {
+                    node = next;
+                    is_partial = true;
+                }
+                None => {
+                    is_partial = false;
+                    break;
+                }
+            }
+        }
+
+        if let Some(command) = resolved {
+            self.pending_chord.clear();
+            self.run_command(command, glyph_brush);
+        } else if !is_partial {
+            self.pending_chord.clear();
+        }
+    }
+
+    /// Runs a single Normal/Select-mode command against every selection,
+    /// mirroring the multi-cursor arrow-key handling above.
+    fn run_command(&mut self, command: Command, glyph_brush: &GlyphBrush<()>) {
+        match command {
+            Command::MoveWordForward => {
+                self.move_all_selections(glyph_brush, |buffer, head, _x_affinity, glyph_brush| {
+                    let location = buffer.next_word_start(head);
+                    let x_affinity = buffer.col_to_x(location.row, location.col, glyph_brush);
+                    (location, x_affinity)
+                });
+            }
+            Command::MoveWordBackward => {
+                self.move_all_selections(glyph_brush, |buffer, head, _x_affinity, glyph_brush| {
+                    let location = buffer.prev_word_start(head);
+                    let x_affinity = buffer.col_to_x(location.row, location.col, glyph_brush);
+                    (location, x_affinity)
+                });
+            }
+            Command::MoveLineStart => {
+                self.move_all_selections(glyph_brush, |buffer, head, _x_affinity, glyph_brush| {
+                    let x_affinity = buffer.col_to_x(head.row, 0, glyph_brush);
+                    (Location { row: head.row, col: 0 }, x_affinity)
+                });
+            }
+            Command::MoveLineEnd => {
+                self.move_all_selections(glyph_brush, |buffer, head, _x_affinity, glyph_brush| {
+                    let col = buffer.line_len(head.row);
+                    let x_affinity = buffer.col_to_x(head.row, col, glyph_brush);
+                    (Location { row: head.row, col }, x_affinity)
+                });
+            }
+            Command::GotoFileStart => {
+                self.move_all_selections(glyph_brush, |_buffer, _head, _x_affinity, _glyph_brush| {
+                    (Location { row: 0, col: 0 }, 0.0)
+                });
+            }
+            Command::DeleteChar => {
+                self.apply_edit_everywhere('\u{7f}', glyph_brush);
+            }
+            Command::OpenLineBelow => {
+                for index in 0..self.selections.len() {
+                    self.selections[index].collapse();
+                    let row = self.selections[index].head.row;
+                    let col = self.line_len(row);
+                    self.selections[index].set_col(col);
+                }
+                self.apply_edit_everywhere('\n', glyph_brush);
+                self.mode = Mode::Insert;
+            }
+            Command::OpenLineAbove => {
+                for index in 0..self.selections.len() {
+                    self.selections[index].collapse();
+                    self.selections[index].set_col(0);
+                }
+                self.apply_edit_everywhere('\n', glyph_brush);
+                // `apply_edit_everywhere` left each selection on the line
+                // the old content moved down to; step back up onto the
+                // fresh blank line it made room for.
+                for index in 0..self.selections.len() {
+                    let row = self.selections[index].head.row.saturating_sub(1);
+                    self.selections[index] = Selection::at(Location { row, col: 0 });
+                }
+                self.mode = Mode::Insert;
+            }
+            Command::EnterInsertBeforeCursor => {
+                for index in 0..self.selections.len() {
+                    self.selections[index].collapse();
+                }
+                self.mode = Mode::Insert;
+            }
+            Command::EnterInsertAfterCursor => {
+                for index in 0..self.selections.len() {
+                    self.selections[index].collapse();
+                    let head = self.selections[index].head;
+                    let col = (head.col + 1).min(self.line_len(head.row));
+                    self.selections[index].set_col(col);
+                    self.selections[index].x_affinity = self.col_to_x(head.row, col, glyph_brush);
+                }
+                self.merge_overlapping_selections();
+                self.mode = Mode::Insert;
+            }
+            Command::ToggleSelectMode => {
+                self.mode = if self.mode == Mode::Select { Mode::Normal } else { Mode::Select };
+            }
+        }
+        self.ensure_cursor_in_view();
+    }
+
+    /// Finds where the next non-whitespace "word" segment starts after
+    /// `location`, wrapping onto following lines (an empty or whitespace-only
+    /// line counts as its own word). Returns the end of the document if there's nothing
+    /// left to move to.
+    fn next_word_start(&self, location: Location) -> Location {
+        let line = self.line_to_string(location.row);
+        let next_on_line = line
+            .split_word_bound_indices()
+            .map(|(byte_idx, word)| (Self::byte_idx_to_col(&line, byte_idx), word))
+            .find(|(col, word)| *col > location.col && !word.trim().is_empty());
+        if let Some((col, _)) = next_on_line {
+            return Location { row: location.row, col };
+        }
+
+        for row in (location.row + 1)..self.line_count() {
+            let line = self.line_to_string(row);
+            if line.trim().is_empty() {
+                return Location { row, col: 0 };
+            }
+            if let Some((byte_idx, _)) = line
+                .split_word_bound_indices()
+                .find(|(_, word)| !word.trim().is_empty())
+            {
+                return Location { row, col: Self::byte_idx_to_col(&line, byte_idx) };
+            }
+        }
+
+        let last_row = self.line_count() - 1;
+        Location { row: last_row, col: self.line_len(last_row) }
+    }
+
+    /// The mirror of `next_word_start`: the closest word-start boundary
+    /// strictly before `location`.
+    fn prev_word_start(&self, location: Location) -> Location {
+        let line = self.line_to_string(location.row);
+        let prev_on_line = line
+            .split_word_bound_indices()
+            .map(|(byte_idx, word)| (Self::byte_idx_to_col(&line, byte_idx), word))
+            .filter(|(col, word)| *col < location.col && !word.trim().is_empty())
+            .last();
+        if let Some((col, _)) = prev_on_line {
+            return Location { row: location.row, col };
+        }
+
+        for row in (0..location.row).rev() {
+            let line = self.line_to_string(row);
+            if line.trim().is_empty() {
+                return Location { row, col: 0 };
+            }
+            if let Some((byte_idx, _)) = line
+                .split_word_bound_indices()
+                .filter(|(_, word)| !word.trim().is_empty())
+                .last()
+            {
+                return Location { row, col: Self::byte_idx_to_col(&line, byte_idx) };
+            }
+        }
+
+        Location::new()
+    }
+
+    /// Grapheme-cluster column of the byte offset `byte_idx` into `line` --
+    /// the word-boundary equivalent of `col_to_char_offset`, since
+    /// `Location::col` counts graphemes, not bytes or chars.
+    fn byte_idx_to_col(line: &str, byte_idx: usize) -> usize {
+        line[..byte_idx].graphemes(true).count()
+    }
+
     pub fn draw(
         &self,
         size: PhysicalSize<u32>,
@@ -389,7 +1639,7 @@ impl Buffer {
         // into a layout pass to simplify drawing.
 
         let x_pad = 10.0;
-        let digit_count = self.lines.len().to_string().chars().count();
+        let digit_count = self.line_count().to_string().chars().count();
         let gutter_offset = x_pad + 30.0 + digit_count as f32 * (SCALE / 2.0);
         let mut y = 5.0 - self.scroll;
 
@@ -399,13 +1649,14 @@ impl Buffer {
             0,
             (digit_count as f32 * (SCALE / 2.0) + x_pad * 2.0) as i32,
             size.height as i32,
-            [0.06, 0.06, 0.06, 1.0],
+            self.theme_colors.gutter,
         );
 
-        let selection_span = self.cursor.selection_span();
+        // Materialize owned line strings up front: `SectionText` needs a
+        // `&str` to borrow for the duration of the queue calls below.
+        let line_texts: Vec<String> = (0..self.line_count()).map(|row| self.line_to_string(row)).collect();
 
-        for (index, (line, highlight)) in self
-            .lines
+        for (index, (line, highlight)) in line_texts
             .iter()
             .zip(self.highlight_info.iter())
             .enumerate()
@@ -420,26 +1671,22 @@ impl Buffer {
 
             let mut line_no_color = [0.4, 0.4, 0.4, 1.0];
 
-            // Paint selection boxes
-            if let Some((start, end)) =
-                selection_span.and_then(|span| span.get_char_indices_for_line(index, line.len()))
-            {
+            // Paint every selection's box that touches this line.
+            for selection in &self.selections {
+                let (start, end) = match selection
+                    .span()
+                    .and_then(|span| span.get_grapheme_indices_for_line(index, self.line_len(index)))
+                {
+                    Some(bounds) => bounds,
+                    None => continue,
+                };
+
                 // TODO: Gah, we should not do this. We should do a single layout pass and add some
                 // methods that lets us query glyph locations.
-                let layout = glyph_brush.fonts().first().unwrap().layout(
-                    line,
-                    Scale::uniform(SCALE),
-                    Point { x: 0.0, y: 0.0 },
-                );
-                let mut x_pos = 0.0;
-                let mut x_start = 0.0;
-                for (i, positioned_glyph) in layout.enumerate().take(end) {
-                    if i == start {
-                        x_start = x_pos;
-                    }
-                    x_pos += positioned_glyph.unpositioned().h_metrics().advance_width;
-                }
-                let width = (x_pos - x_start) as i32;
+                let advances = Self::grapheme_advances(line, glyph_brush);
+                let x_start = advances[start.min(advances.len() - 1)];
+                let x_end = advances[end.min(advances.len() - 1)];
+                let width = (x_end - x_start) as i32;
                 let x = x_start as i32;
 
                 rect_brush.queue_rectangle(
@@ -447,41 +1694,44 @@ impl Buffer {
                     y as i32,
                     width,
                     SCALE as i32,
-                    [0.0, 0.0, 1.0, 0.1],
+                    self.theme_colors.selection,
                 );
             }
 
-            if index == self.cursor.location.row {
-                line_no_color = [1.0, 1.0, 1.0, 1.0];
+            let carets_on_line: Vec<&Selection> = self
+                .selections
+                .iter()
+                .filter(|selection| selection.head.row == index)
+                .collect();
 
-                let mut layout = glyph_brush.fonts().first().unwrap().layout(
-                    line,
-                    Scale::uniform(SCALE),
-                    Point { x: 0.0, y: 0.0 },
-                );
-                let mut x_pos = 0.0;
-                for _ in 0..self.cursor.location.col {
-                    let positioned_glyph = layout.next().unwrap();
-                    x_pos += positioned_glyph.unpositioned().h_metrics().advance_width;
-                }
+            if !carets_on_line.is_empty() {
+                line_no_color = [1.0, 1.0, 1.0, 1.0];
 
-                let cursor_x = gutter_offset + x_pos;
                 // active line
                 rect_brush.queue_rectangle(
                     0,
                     y as i32,
                     size.width as i32,
                     SCALE as i32,
-                    [1.0, 1.0, 1.0, 0.05],
+                    self.theme_colors.active_line,
                 );
 
-                rect_brush.queue_rectangle(
-                    cursor_x as i32 - 2,
-                    y as i32,
-                    4,
-                    SCALE as i32,
-                    [1.0, 1.0, 1.0, 1.0],
-                );
+                let advances = Self::grapheme_advances(line, glyph_brush);
+                let shape = self.cursor_shape();
+                for selection in carets_on_line {
+                    let col = selection.head.col;
+                    let x_pos = advances[col.min(advances.len() - 1)];
+                    let cursor_x = gutter_offset + x_pos;
+                    // Width of the glyph under the cursor, or a fallback
+                    // half-cell width past the end of the line where
+                    // there's no glyph to measure.
+                    let width = if col + 1 < advances.len() {
+                        advances[col + 1] - x_pos
+                    } else {
+                        SCALE / 2.0
+                    };
+                    draw_caret(rect_brush, shape, cursor_x, y, width, [1.0, 1.0, 1.0, 1.0]);
+                }
             }
 
             let line_number = index + 1;
@@ -518,3 +1768,120 @@ impl Buffer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Typing at two cursors on the same row must leave both cursors right
+    // after the character they just typed.
+    #[test]
+    fn apply_edit_everywhere_keeps_same_row_cursors_consistent() {
+        let mut buffer = Buffer::for_test("abc");
+        buffer.selections = vec![
+            Selection::at(Location { row: 0, col: 0 }),
+            Selection::at(Location { row: 0, col: 3 }),
+        ];
+
+        buffer.apply_edit_everywhere_core('X');
+
+        assert_eq!(buffer.text.to_string(), "XabcX");
+        assert_eq!(buffer.selections[0].head, Location { row: 0, col: 1 });
+        assert_eq!(buffer.selections[1].head, Location { row: 0, col: 5 });
+    }
+
+    // Pressing Enter at two cursors on different rows must shift the lower
+    // cursor's row down by one line for every row inserted above it.
+    #[test]
+    fn apply_edit_everywhere_shifts_rows_after_later_cursors_insert_lines() {
+        let mut buffer = Buffer::for_test("one\ntwo\nthree");
+        buffer.selections = vec![
+            Selection::at(Location { row: 0, col: 3 }),
+            Selection::at(Location { row: 1, col: 3 }),
+        ];
+
+        buffer.apply_edit_everywhere_core('\n');
+
+        assert_eq!(buffer.text.to_string(), "one\n\ntwo\n\nthree");
+        assert_eq!(buffer.selections[0].head, Location { row: 1, col: 0 });
+        assert_eq!(buffer.selections[1].head, Location { row: 3, col: 0 });
+        assert_eq!(buffer.highlight_info.len(), buffer.line_count());
+        assert_eq!(buffer.line_states.len(), buffer.line_count());
+    }
+
+    #[test]
+    fn transaction_apply_returns_exact_inverse() {
+        let mut rope = Rope::from_str("hello world");
+        let transaction = Transaction {
+            changes: vec![Change {
+                start: 5,
+                end: 11,
+                inserted: Some(" there".to_string()),
+            }],
+            selections_before: vec![Selection::at(Location { row: 0, col: 5 })],
+            selections_after: vec![Selection::at(Location { row: 0, col: 11 })],
+        };
+
+        let inverse = transaction.apply(&mut rope);
+        assert_eq!(rope.to_string(), "hello there");
+
+        let redo = inverse.apply(&mut rope);
+        assert_eq!(rope.to_string(), "hello world");
+        assert_eq!(redo.changes.len(), transaction.changes.len());
+    }
+
+    #[test]
+    fn transaction_coalesce_runs_newer_changes_first() {
+        let mut combined = Transaction {
+            changes: vec![Change {
+                start: 0,
+                end: 1,
+                inserted: None,
+            }],
+            selections_before: vec![Selection::at(Location::new())],
+            selections_after: vec![Selection::at(Location { row: 0, col: 1 })],
+        };
+        let newer = Transaction {
+            changes: vec![Change {
+                start: 1,
+                end: 2,
+                inserted: None,
+            }],
+            selections_before: vec![Selection::at(Location { row: 0, col: 1 })],
+            selections_after: vec![Selection::at(Location { row: 0, col: 2 })],
+        };
+
+        combined.coalesce(newer.clone());
+
+        assert_eq!(combined.changes[0].start, 1);
+        assert_eq!(combined.changes[1].start, 0);
+        assert_eq!(combined.selections_before, newer.selections_before);
+    }
+
+    // A space is still `EditKind::Insert`, but it's also a word boundary --
+    // typing a whole sentence shouldn't coalesce into a single undo step.
+    #[test]
+    fn record_undo_breaks_coalescing_at_word_boundary() {
+        let mut buffer = Buffer::for_test("");
+        for c in "foo bar".chars() {
+            buffer.apply_edit_everywhere_core(c);
+        }
+
+        assert_eq!(buffer.text.to_string(), "foo bar");
+        assert_eq!(buffer.undo_stack.len(), 2);
+    }
+
+    #[test]
+    fn word_motion_next_and_prev_agree() {
+        let buffer = Buffer::for_test("foo bar\nbaz");
+
+        let bar = buffer.next_word_start(Location::new());
+        assert_eq!(bar, Location { row: 0, col: 4 });
+
+        let baz = buffer.next_word_start(bar);
+        assert_eq!(baz, Location { row: 1, col: 0 });
+
+        assert_eq!(buffer.prev_word_start(baz), bar);
+        assert_eq!(buffer.prev_word_start(bar), Location::new());
+    }
+}